@@ -1,14 +1,102 @@
+#[cfg(feature = "std")]
 use std::any::Any;
+#[cfg(feature = "std")]
+use std::fmt::Debug;
+#[cfg(feature = "std")]
 use std::any::type_name;
-use std::any::type_name_of_val;
+#[cfg(feature = "std")]
 use std::any::TypeId;
+#[cfg(feature = "std")]
+use std::boxed::Box;
+#[cfg(all(feature = "std", not(feature = "hashbrown")))]
+use std::collections::hash_map::RandomState;
+#[cfg(all(feature = "std", not(feature = "hashbrown")))]
 use std::collections::hash_map::Keys;
+#[cfg(all(feature = "std", not(feature = "hashbrown")))]
 use std::collections::HashMap;
+#[cfg(feature = "std")]
+use std::hash::BuildHasher;
+#[cfg(feature = "std")]
 use std::hash::Hash;
+#[cfg(feature = "std")]
+use std::marker::PhantomData;
+#[cfg(feature = "std")]
+use std::cell::Cell;
+#[cfg(feature = "std")]
+use std::cell::RefCell;
+#[cfg(feature = "std")]
+use std::cell::UnsafeCell;
+#[cfg(feature = "std")]
+use std::ops::Deref;
+#[cfg(feature = "std")]
+use std::ops::DerefMut;
+#[cfg(feature = "std")]
+use std::string::String;
+#[cfg(feature = "std")]
+use std::string::ToString;
+#[cfg(feature = "std")]
 use std::vec::IntoIter;
+#[cfg(feature = "std")]
+use std::vec::Vec;
 
+#[cfg(not(feature = "std"))]
+use core::any::Any;
+#[cfg(not(feature = "std"))]
+use core::fmt::Debug;
+#[cfg(not(feature = "std"))]
+use core::any::type_name;
+#[cfg(not(feature = "std"))]
+use core::any::TypeId;
+#[cfg(not(feature = "std"))]
+use core::hash::BuildHasher;
+#[cfg(not(feature = "std"))]
+use core::hash::Hash;
+#[cfg(not(feature = "std"))]
+use core::marker::PhantomData;
+#[cfg(not(feature = "std"))]
+use core::cell::Cell;
+#[cfg(not(feature = "std"))]
+use core::cell::RefCell;
+#[cfg(not(feature = "std"))]
+use core::cell::UnsafeCell;
+#[cfg(not(feature = "std"))]
+use core::ops::Deref;
+#[cfg(not(feature = "std"))]
+use core::ops::DerefMut;
+#[cfg(not(feature = "std"))]
+use alloc::boxed::Box;
+#[cfg(not(feature = "std"))]
+use alloc::string::String;
+#[cfg(not(feature = "std"))]
+use alloc::string::ToString;
+#[cfg(not(feature = "std"))]
+use alloc::vec::IntoIter;
+#[cfg(not(feature = "std"))]
+use alloc::vec::Vec;
+
+#[cfg(feature = "hashbrown")]
+use hashbrown::hash_map::DefaultHashBuilder as RandomState;
+#[cfg(feature = "hashbrown")]
+use hashbrown::hash_map::Keys;
+#[cfg(feature = "hashbrown")]
+use hashbrown::HashMap;
+
+#[cfg(feature = "std")]
 use thiserror::Error;
 
+#[cfg(not(any(feature = "std", feature = "hashbrown")))]
+compile_error!(
+    "SafeAnyMap needs a HashMap implementation: enable the `std` feature, or build with \
+     `--no-default-features --features hashbrown` for no_std targets."
+);
+
+/// Error type for [`SafeAnyMap`] and friends.
+///
+/// Under the default `std` feature this derives its `Display`/`Error` impl
+/// via `thiserror`; under `no_std` that dependency is unavailable, so the
+/// same variants get a hand-written `core::fmt::Display` + `core::error::Error`
+/// impl below with the identical messages.
+#[cfg(feature = "std")]
 #[derive(Error, Debug)]
 pub enum SafeAnyMapError {
     #[error("Double inserts disallowed. Enable them by creating SafeAnyMap with `::new_double_inserts`")]
@@ -24,18 +112,132 @@ pub enum SafeAnyMapError {
     }
 }
 
+#[cfg(not(feature = "std"))]
+#[derive(Debug)]
+pub enum SafeAnyMapError {
+    DoubleInsert,
+    ConflictingValueType {
+        got: &'static str,
+        exist: String
+    },
+    FailedDowncast {
+        got: &'static str
+    }
+}
+
+#[cfg(not(feature = "std"))]
+impl core::fmt::Display for SafeAnyMapError {
+    fn fmt(&self, f: &mut core::fmt::Formatter<'_>) -> core::fmt::Result {
+        match self {
+            SafeAnyMapError::DoubleInsert => write!(f, "Double inserts disallowed. Enable them by creating SafeAnyMap with `::new_double_inserts`"),
+            SafeAnyMapError::ConflictingValueType { got, exist } => write!(f, "Conflicting Value Type `{got:?}` must match existing value type `{exist:?}`"),
+            SafeAnyMapError::FailedDowncast { got } => write!(f, "Downcast failed for given type `{got:?}`"),
+        }
+    }
+}
+
+#[cfg(not(feature = "std"))]
+impl core::error::Error for SafeAnyMapError {}
+
+/// A key bound to the value type it is expected to point at.
+///
+/// `Key<K, T>` wraps a plain `K` together with a `TypeId::of::<T>()`
+/// marker, so [`SafeAnyMap::insert_keyed`] / [`SafeAnyMap::get_keyed`] /
+/// [`SafeAnyMap::remove_keyed`] never need a turbofish and can't be
+/// called with a mismatched `T` at the type level. It resolves to the
+/// same `K` + `TypeId` pair the untyped API already tracks in
+/// `relations`, so keyed and untyped access can be mixed freely.
+pub struct Key<K, T> {
+    key: K,
+    _marker: PhantomData<fn() -> T>,
+}
+
+impl<K, T> Key<K, T> {
+    pub fn new(key: K) -> Self {
+        Key { key, _marker: PhantomData }
+    }
+}
+
+impl<K: Clone, T> Clone for Key<K, T> {
+    fn clone(&self) -> Self {
+        Key::new(self.key.clone())
+    }
+}
+
+/// A view into a single entry of a [`SafeAnyMap`], obtained from
+/// [`SafeAnyMap::entry`].
+///
+/// Unlike [`std::collections::hash_map::Entry`], a conflicting entry (the
+/// key already holds a value of a different type) is its own variant
+/// instead of being folded into `Occupied`, so `or_insert`/`or_insert_with`
+/// surface the type mismatch as a [`SafeAnyMapError`] instead of silently
+/// overwriting the existing value.
+pub enum Entry<'a, K, S, T> {
+    Occupied { map: &'a mut SafeAnyMap<K, S>, key: K, _marker: PhantomData<T> },
+    Vacant { map: &'a mut SafeAnyMap<K, S>, key: K, _marker: PhantomData<T> },
+    Conflicting { map: &'a mut SafeAnyMap<K, S>, key: K, exist: String, _marker: PhantomData<T> },
+}
+
+impl<'a, K, S, T> Entry<'a, K, S, T>
+where
+    K: Hash + Eq + Clone + Debug,
+    S: BuildHasher + Default,
+    T: Any + Hash + 'static,
+{
+    /// Inserts `default` if the entry is vacant, then returns a mutable
+    /// reference to the value. Returns an error without touching the map
+    /// if the entry is occupied by a different type.
+    pub fn or_insert(self, default: T) -> Result<&'a mut T, SafeAnyMapError> {
+        self.or_insert_with(|| default)
+    }
+
+    /// Like [`Entry::or_insert`], but the default is only computed if the
+    /// entry is vacant.
+    pub fn or_insert_with<F: FnOnce() -> T>(self, f: F) -> Result<&'a mut T, SafeAnyMapError> {
+        match self {
+            Entry::Occupied { map, key, .. } => Ok(map.get_mut::<T>(&key).expect("relation was just checked to match T")),
+            Entry::Vacant { map, key, .. } => {
+                map.insert(key.clone(), f())?;
+                Ok(map.get_mut::<T>(&key).expect("value was just inserted"))
+            }
+            Entry::Conflicting { exist, .. } => Err(SafeAnyMapError::ConflictingValueType { got: type_name::<T>(), exist }),
+        }
+    }
+
+    /// Runs `f` against the existing value if the entry is occupied,
+    /// leaving vacant and conflicting entries untouched.
+    pub fn and_modify<F: FnOnce(&mut T)>(mut self, f: F) -> Self {
+        if let Entry::Occupied { map, key, .. } = &mut self {
+            if let Some(value) = map.get_mut::<T>(key) {
+                f(value);
+            }
+        }
+        self
+    }
+}
+
 /// A dynamically typed store for information about the current context.
-/// Our safety for this store comes in two ways:
-/// -. On destruction, we drop the value with `Box::from_raw` ONCE avoiding memory leak and double free's.
-pub struct SafeAnyMap<K> {
+/// Values live behind an owned `Box<dyn Any>`, so the backing `HashMap`
+/// drops each entry exactly once on its own -- there is no raw pointer
+/// bookkeeping and no way to double-free or hand out aliased `&mut`s.
+pub struct SafeAnyMap<K, S = RandomState> {
     /// Item Ids are used to identify the type of item stored.
-    items: HashMap<K, *mut dyn Any>,
-    relations: HashMap<K, TypeId>,
+    items: HashMap<K, Box<dyn Any>, S>,
+    /// `TypeId` plus the matching `type_name`, so a conflicting insert can
+    /// report what's actually stored instead of just the rejected type.
+    relations: HashMap<K, (TypeId, &'static str), S>,
     allow_double_inserts: bool
 }
 
-impl<K> SafeAnyMap<K>
-where K: Hash + Eq + Clone + std::fmt::Debug {
+impl<K> Default for SafeAnyMap<K, RandomState>
+where K: Hash + Eq + Clone + Debug {
+    fn default() -> Self {
+        Self::new()
+    }
+}
+
+impl<K> SafeAnyMap<K, RandomState>
+where K: Hash + Eq + Clone + Debug {
     pub fn new() -> Self {
         SafeAnyMap {
             items: HashMap::new(),
@@ -51,50 +253,76 @@ where K: Hash + Eq + Clone + std::fmt::Debug {
             allow_double_inserts: true
         }
     }
+}
+
+impl<K, S> SafeAnyMap<K, S>
+where
+    K: Hash + Eq + Clone + Debug,
+    S: BuildHasher + Default + Clone,
+{
+    /// Builds an empty map that hashes `items`/`relations` with `hasher`
+    /// instead of the default `RandomState`. Handy when `K` is something
+    /// cheap to hash (small integer ids, `TypeId`-like values) where
+    /// SipHash is pure overhead.
+    pub fn with_hasher(hasher: S) -> Self {
+        SafeAnyMap {
+            items: HashMap::with_hasher(hasher.clone()),
+            relations: HashMap::with_hasher(hasher),
+            allow_double_inserts: false
+        }
+    }
+
+    /// Same as [`SafeAnyMap::with_hasher`], but pre-allocates capacity for
+    /// both backing maps.
+    pub fn with_capacity_and_hasher(capacity: usize, hasher: S) -> Self {
+        SafeAnyMap {
+            items: HashMap::with_capacity_and_hasher(capacity, hasher.clone()),
+            relations: HashMap::with_capacity_and_hasher(capacity, hasher),
+            allow_double_inserts: false
+        }
+    }
+}
 
+impl<K, S> SafeAnyMap<K, S>
+where
+    K: Hash + Eq + Clone + Debug,
+    S: BuildHasher + Default,
+{
     /// Same as [std::collections::HashMap::keys]
-    pub fn keys(&self) -> Keys<K, *mut dyn Any> {
+    pub fn keys(&self) -> Keys<'_, K, Box<dyn Any>> {
         self.items.keys()
     }
 
     /// Same as [std::collections::HashMap::values]
     pub fn values(&self) -> IntoIter<&dyn Any> {
-        unsafe {
-            let vec = self.items.values().map(|v| &**v).collect::<Vec<_>>();
-            vec.into_iter()
-        }
+        let vec = self.items.values().map(|v| v.as_ref()).collect::<Vec<_>>();
+        vec.into_iter()
     }
 
     /// Same as [std::collections::HashMap::values_mut]
-    /// Unsafe cause there's no garauntee that the type will match the relations store
-    /// This is up to the caller to do.
-    pub unsafe fn values_mut(&mut self) -> IntoIter<&mut dyn Any> {
-        unsafe {
-            let vec = self.items.values().map(|v| &mut **v).collect::<Vec<_>>();
-            vec.into_iter()
-        }
+    pub fn values_mut(&mut self) -> IntoIter<&mut dyn Any> {
+        let vec = self.items.values_mut().map(|v| v.as_mut()).collect::<Vec<_>>();
+        vec.into_iter()
     }
 
     /// If the value exists within the store, `Ok(Some(Box<T>))` is returned.
-    pub fn insert<T: Sized>(&mut self, key: K, value: T) -> Result<Option<Box<T>>, SafeAnyMapError>
+    pub fn insert<T>(&mut self, key: K, value: T) -> Result<Option<Box<T>>, SafeAnyMapError>
     where
         T: Any + Hash + 'static,
     {
-        let boxed = Box::into_raw(Box::new(value));
+        let boxed: Box<dyn Any> = Box::new(value);
 
         if !self.allow_double_inserts && self.items.contains_key(&key) {
             return Err(SafeAnyMapError::DoubleInsert);
         }
 
-        if !self.check_or_insert_existing_relation::<T>(&key, boxed) {
-            return Err(SafeAnyMapError::ConflictingValueType { got: type_name::<T>(), exist: type_name_of_val(&boxed).to_string() });
+        if !self.check_or_insert_existing_relation::<T>(&key, boxed.as_ref()) {
+            let exist = self.relations.get(&key).map(|(_, name)| *name).unwrap_or("<unknown>");
+            return Err(SafeAnyMapError::ConflictingValueType { got: type_name::<T>(), exist: exist.to_string() });
         }
 
         if let Some(bx) = self.items.insert(key, boxed) {
-            // Safety: Box is only converted once here, its not possible to convert after
-            //         we delete it.
-            let value = unsafe { Box::from_raw(bx) };
-            if let Ok(v) = value.downcast::<T>() {
+            if let Ok(v) = bx.downcast::<T>() {
                 return Ok(Some(v));
             } else {
                 return Err(SafeAnyMapError::FailedDowncast { got: type_name::<T>() })
@@ -104,43 +332,38 @@ where K: Hash + Eq + Clone + std::fmt::Debug {
         Ok(None)
     }
 
-    pub fn get<T: Sized>(&self, key: &K) -> Option<&T>
+    pub fn get<T>(&self, key: &K) -> Option<&T>
     where
         T: Any + Hash + 'static,
     {
         // first check the existing relation
-        if let Some(actual) = self.relations.get(key) {
+        if let Some((actual, _)) = self.relations.get(key) {
             // we cant use contains because the type is not the same
             if *actual != TypeId::of::<T>() {
                 return None;
             }
 
             if let Some(item) = self.items.get(key) {
-                // deref *mut dyn Any -> dyn Any -> &dyn Any
-                let value = unsafe { &**(item as *const *mut dyn Any) };
-
-                return value.downcast_ref::<T>();
+                return item.downcast_ref::<T>();
             }
         }
 
         None
     }
 
-    pub fn get_mut<T: Sized>(&mut self, key: &K) -> Option<&mut T>
+    pub fn get_mut<T>(&mut self, key: &K) -> Option<&mut T>
     where
         T: Any + Hash + 'static,
     {
         // first check the existing relation
-        if let Some(actual) = self.relations.get(key) {
+        if let Some((actual, _)) = self.relations.get(key) {
             // we cant use contains because the type is not the same
             if *actual != TypeId::of::<T>() {
                 return None;
             }
 
             if let Some(item) = self.items.get_mut(key) {
-                let value = unsafe { &mut **(item as *const *mut dyn Any) };
-
-                return value.downcast_mut::<T>();
+                return item.downcast_mut::<T>();
             }
         }
 
@@ -149,11 +372,11 @@ where K: Hash + Eq + Clone + std::fmt::Debug {
 
     /// `T` required to remove so we know we're trying to remove
     /// the right thing.
-    pub fn remove<T: Sized>(&mut self, key: &K) -> Option<T>
+    pub fn remove<T>(&mut self, key: &K) -> Option<T>
     where
         T: Any + Hash + 'static,
     {
-        if let Some(actual) = self.relations.get(key) {
+        if let Some((actual, _)) = self.relations.get(key) {
             if *actual != TypeId::of::<T>() {
                 return None;
             }
@@ -161,12 +384,8 @@ where K: Hash + Eq + Clone + std::fmt::Debug {
             if self.items.contains_key(key) {
                 self.relations.remove(key);
 
-                // SAFETY: Drop the value with box
-                // we avoid double free since drop is only called here.
                 if let Some(item) = self.items.remove(key) {
-                    let value = unsafe { Box::from_raw(item) };
-
-                    if let Ok(v) = value.downcast::<T>() {
+                    if let Ok(v) = item.downcast::<T>() {
                         return Some(*v);
                     } else {
                         return None;
@@ -178,27 +397,737 @@ where K: Hash + Eq + Clone + std::fmt::Debug {
         None
     }
 
+    /// Same as [`SafeAnyMap::insert`], but the key carries `T` so there is
+    /// no turbofish and no way to pass a key that was minted for a
+    /// different type.
+    pub fn insert_keyed<T>(&mut self, key: Key<K, T>, value: T) -> Result<Option<Box<T>>, SafeAnyMapError>
+    where
+        T: Any + Hash + 'static,
+    {
+        self.insert(key.key, value)
+    }
+
+    /// Same as [`SafeAnyMap::get`], but the key carries `T` so there is no
+    /// turbofish.
+    pub fn get_keyed<T>(&self, key: &Key<K, T>) -> Option<&T>
+    where
+        T: Any + Hash + 'static,
+    {
+        self.get(&key.key)
+    }
+
+    /// Same as [`SafeAnyMap::remove`], but the key carries `T` so there is
+    /// no turbofish.
+    pub fn remove_keyed<T>(&mut self, key: &Key<K, T>) -> Option<T>
+    where
+        T: Any + Hash + 'static,
+    {
+        self.remove(&key.key)
+    }
+
+    /// Returns the [`Entry`] for the given key, so a caller can
+    /// get-or-insert a value while still honoring the `relations` type
+    /// check -- an occupied entry whose stored type differs from `T`
+    /// surfaces as [`Entry::Conflicting`] instead of silently overwriting
+    /// the existing value.
+    pub fn entry<T>(&mut self, key: K) -> Entry<'_, K, S, T>
+    where
+        T: Any + Hash + 'static,
+    {
+        match self.relations.get(&key) {
+            Some((actual, _)) if *actual == TypeId::of::<T>() => Entry::Occupied { map: self, key, _marker: PhantomData },
+            Some((_, name)) => Entry::Conflicting { exist: name.to_string(), map: self, key, _marker: PhantomData },
+            None => Entry::Vacant { map: self, key, _marker: PhantomData },
+        }
+    }
+
     fn check_or_insert_existing_relation<T: 'static>(
         &mut self,
         key: &K,
-        value: *mut dyn Any,
+        value: &dyn Any,
     ) -> bool {
         let requested_type_id = TypeId::of::<T>();
-        let value = unsafe { &*(value as *const dyn Any) };
 
-        if let Some(actual) = self.relations.get(key) {
+        if let Some((actual, _)) = self.relations.get(key) {
             // if we find the relation type within our existing relations, we can check if the value is of the same type
-            if *actual == requested_type_id {
-                return value.is::<T>();
+            *actual == requested_type_id && value.is::<T>()
+        } else if value.is::<T>() {
+            self.relations.insert(key.clone(), (requested_type_id, type_name::<T>()));
+            true
+        } else {
+            false
+        }
+    }
+}
+
+/// Like [`std::any::Any`], but for erased values that can also be cloned.
+///
+/// `dyn Any` has no way to duplicate the value it erases, so
+/// [`SafeAnyCloneMap`] cannot be `Clone` while it stores `Box<dyn Any>`.
+/// `CloneAny` is blanket-implemented for every `T: Any + Clone` and adds
+/// `clone_box`, which is all a `Box<dyn CloneAny>` needs to duplicate
+/// itself without knowing the concrete type underneath.
+pub trait CloneAny: Any {
+    fn clone_box(&self) -> Box<dyn CloneAny>;
+    fn as_any(&self) -> &dyn Any;
+    fn as_any_mut(&mut self) -> &mut dyn Any;
+    fn into_any(self: Box<Self>) -> Box<dyn Any>;
+}
+
+impl<T: Any + Clone> CloneAny for T {
+    fn clone_box(&self) -> Box<dyn CloneAny> {
+        Box::new(self.clone())
+    }
+
+    fn as_any(&self) -> &dyn Any {
+        self
+    }
+
+    fn as_any_mut(&mut self) -> &mut dyn Any {
+        self
+    }
+
+    fn into_any(self: Box<Self>) -> Box<dyn Any> {
+        self
+    }
+}
+
+/// A [`SafeAnyMap`] variant whose values are required to be `Clone`, so the
+/// whole store can be snapshotted with [`Clone::clone`].
+pub struct SafeAnyCloneMap<K> {
+    items: HashMap<K, Box<dyn CloneAny>>,
+    /// `TypeId` plus the matching `type_name`, so a conflicting insert can
+    /// report what's actually stored instead of just the rejected type.
+    relations: HashMap<K, (TypeId, &'static str)>,
+    allow_double_inserts: bool
+}
+
+impl<K> Default for SafeAnyCloneMap<K>
+where K: Hash + Eq + Clone + Debug {
+    fn default() -> Self {
+        Self::new()
+    }
+}
+
+impl<K> SafeAnyCloneMap<K>
+where K: Hash + Eq + Clone + Debug {
+    pub fn new() -> Self {
+        SafeAnyCloneMap {
+            items: HashMap::new(),
+            relations: HashMap::new(),
+            allow_double_inserts: false
+        }
+    }
+
+    pub fn new_double_inserts() -> Self {
+        SafeAnyCloneMap {
+            items: HashMap::new(),
+            relations: HashMap::new(),
+            allow_double_inserts: true
+        }
+    }
+
+    pub fn insert<T>(&mut self, key: K, value: T) -> Result<Option<Box<T>>, SafeAnyMapError>
+    where
+        T: Any + Clone + Hash + 'static,
+    {
+        let boxed: Box<dyn CloneAny> = Box::new(value);
+
+        if !self.allow_double_inserts && self.items.contains_key(&key) {
+            return Err(SafeAnyMapError::DoubleInsert);
+        }
+
+        if !self.check_or_insert_existing_relation::<T>(&key, boxed.as_any()) {
+            let exist = self.relations.get(&key).map(|(_, name)| *name).unwrap_or("<unknown>");
+            return Err(SafeAnyMapError::ConflictingValueType { got: type_name::<T>(), exist: exist.to_string() });
+        }
+
+        if let Some(bx) = self.items.insert(key, boxed) {
+            if let Ok(v) = bx.into_any().downcast::<T>() {
+                return Ok(Some(v));
             } else {
-                return false;
+                return Err(SafeAnyMapError::FailedDowncast { got: type_name::<T>() })
             }
-        } else {
-            if value.is::<T>() {
-                self.relations.insert(key.clone(), requested_type_id);
-                return true;
+        }
+
+        Ok(None)
+    }
+
+    pub fn get<T>(&self, key: &K) -> Option<&T>
+    where
+        T: Any + Clone + Hash + 'static,
+    {
+        if let Some((actual, _)) = self.relations.get(key) {
+            if *actual != TypeId::of::<T>() {
+                return None;
+            }
+
+            if let Some(item) = self.items.get(key) {
+                return (**item).as_any().downcast_ref::<T>();
+            }
+        }
+
+        None
+    }
+
+    pub fn get_mut<T>(&mut self, key: &K) -> Option<&mut T>
+    where
+        T: Any + Clone + Hash + 'static,
+    {
+        if let Some((actual, _)) = self.relations.get(key) {
+            if *actual != TypeId::of::<T>() {
+                return None;
+            }
+
+            if let Some(item) = self.items.get_mut(key) {
+                return (**item).as_any_mut().downcast_mut::<T>();
+            }
+        }
+
+        None
+    }
+
+    pub fn remove<T>(&mut self, key: &K) -> Option<T>
+    where
+        T: Any + Clone + Hash + 'static,
+    {
+        if let Some((actual, _)) = self.relations.get(key) {
+            if *actual != TypeId::of::<T>() {
+                return None;
             }
+
+            if self.items.contains_key(key) {
+                self.relations.remove(key);
+
+                if let Some(item) = self.items.remove(key) {
+                    if let Ok(v) = item.into_any().downcast::<T>() {
+                        return Some(*v);
+                    } else {
+                        return None;
+                    }
+                }
+            }
+        }
+
+        None
+    }
+
+    fn check_or_insert_existing_relation<T: 'static>(
+        &mut self,
+        key: &K,
+        value: &dyn Any,
+    ) -> bool {
+        let requested_type_id = TypeId::of::<T>();
+
+        if let Some((actual, _)) = self.relations.get(key) {
+            *actual == requested_type_id && value.is::<T>()
+        } else if value.is::<T>() {
+            self.relations.insert(key.clone(), (requested_type_id, type_name::<T>()));
+            true
+        } else {
             false
         }
     }
 }
+
+impl<K> Clone for SafeAnyCloneMap<K>
+where K: Hash + Eq + Clone + Debug {
+    fn clone(&self) -> Self {
+        let items = self
+            .items
+            .iter()
+            .map(|(k, v)| (k.clone(), (**v).clone_box()))
+            .collect();
+
+        SafeAnyCloneMap {
+            items,
+            relations: self.relations.clone(),
+            allow_double_inserts: self.allow_double_inserts
+        }
+    }
+}
+
+/// Backing storage for a single [`SharedSafeAnyMap`] entry: an owned,
+/// type-erased value plus its own borrow-state flag, independent of the
+/// outer map's structure.
+///
+/// `0` means unborrowed, a positive count means that many live [`Ref`]s,
+/// and `-1` means a live [`RefMut`] — the same encoding
+/// [`std::cell::RefCell`] uses internally.
+struct ErasedEntry {
+    value: UnsafeCell<Box<dyn Any>>,
+    borrow: Cell<isize>,
+}
+
+/// A shared borrow of a value held in a [`SharedSafeAnyMap`], returned by
+/// [`SharedSafeAnyMap::get`].
+///
+/// Behaves like [`std::cell::Ref`]: dropping it releases the borrow.
+pub struct Ref<'a, T: ?Sized> {
+    value: &'a T,
+    borrow: &'a Cell<isize>,
+}
+
+impl<'a, T: ?Sized> Deref for Ref<'a, T> {
+    type Target = T;
+
+    fn deref(&self) -> &T {
+        self.value
+    }
+}
+
+impl<'a, T: ?Sized> Drop for Ref<'a, T> {
+    fn drop(&mut self) {
+        self.borrow.set(self.borrow.get() - 1);
+    }
+}
+
+/// An exclusive borrow of a value held in a [`SharedSafeAnyMap`], returned
+/// by [`SharedSafeAnyMap::get_mut`].
+///
+/// Behaves like [`std::cell::RefMut`]: dropping it releases the borrow.
+pub struct RefMut<'a, T: ?Sized> {
+    value: &'a mut T,
+    borrow: &'a Cell<isize>,
+}
+
+impl<'a, T: ?Sized> Deref for RefMut<'a, T> {
+    type Target = T;
+
+    fn deref(&self) -> &T {
+        self.value
+    }
+}
+
+impl<'a, T: ?Sized> DerefMut for RefMut<'a, T> {
+    fn deref_mut(&mut self) -> &mut T {
+        self.value
+    }
+}
+
+impl<'a, T: ?Sized> Drop for RefMut<'a, T> {
+    fn drop(&mut self) {
+        self.borrow.set(0);
+    }
+}
+
+/// A [`SafeAnyMap`] variant whose values live behind cell-style interior
+/// mutability, so `insert`/`get`/`get_mut` all take `&self` instead of
+/// `&mut self`. A single shared handle (e.g. behind an `Rc`) can hand out
+/// typed [`Ref`]/[`RefMut`] guards without any caller needing exclusive
+/// access to the map itself.
+///
+/// Each entry tracks its own borrow state exactly like a
+/// [`std::cell::RefCell`]: taking a [`Ref`] while a [`RefMut`] to the same
+/// key is outstanding (or vice versa) panics instead of aliasing. The
+/// map's structure lives behind its own `RefCell`, so inserting a new key
+/// is unaffected by borrows on other keys; replacing an occupied key's
+/// value while that key is currently borrowed also panics, since dropping
+/// the old value out from under a live guard would be unsound.
+pub struct SharedSafeAnyMap<K> {
+    items: RefCell<HashMap<K, Box<ErasedEntry>>>,
+    /// `TypeId` plus the matching `type_name`, so a conflicting insert can
+    /// report what's actually stored instead of just the rejected type.
+    relations: RefCell<HashMap<K, (TypeId, &'static str)>>,
+    allow_double_inserts: bool,
+}
+
+impl<K> Default for SharedSafeAnyMap<K>
+where
+    K: Hash + Eq + Clone + Debug,
+{
+    fn default() -> Self {
+        Self::new()
+    }
+}
+
+impl<K> SharedSafeAnyMap<K>
+where
+    K: Hash + Eq + Clone + Debug,
+{
+    pub fn new() -> Self {
+        SharedSafeAnyMap {
+            items: RefCell::new(HashMap::new()),
+            relations: RefCell::new(HashMap::new()),
+            allow_double_inserts: false,
+        }
+    }
+
+    pub fn new_double_inserts() -> Self {
+        SharedSafeAnyMap {
+            items: RefCell::new(HashMap::new()),
+            relations: RefCell::new(HashMap::new()),
+            allow_double_inserts: true,
+        }
+    }
+
+    /// Inserts `value` under `key`.
+    ///
+    /// Returns [`SafeAnyMapError::DoubleInsert`] if the key is already
+    /// occupied and the map wasn't created with [`Self::new_double_inserts`],
+    /// and [`SafeAnyMapError::ConflictingValueType`] if it's occupied by a
+    /// different `T`; both errors are returned even if the key is currently
+    /// borrowed. Panics if the insert would replace a currently borrowed
+    /// value of the same `T`, since that would invalidate the outstanding
+    /// [`Ref`]/[`RefMut`].
+    pub fn insert<T>(&self, key: K, value: T) -> Result<(), SafeAnyMapError>
+    where
+        T: Any + Hash + 'static,
+    {
+        let requested = TypeId::of::<T>();
+        let mut relations = self.relations.borrow_mut();
+        let mut items = self.items.borrow_mut();
+
+        if !self.allow_double_inserts && items.contains_key(&key) {
+            return Err(SafeAnyMapError::DoubleInsert);
+        }
+
+        if let Some((actual, name)) = relations.get(&key) {
+            if *actual != requested {
+                return Err(SafeAnyMapError::ConflictingValueType {
+                    got: type_name::<T>(),
+                    exist: name.to_string(),
+                });
+            }
+
+            let existing = items.get(&key).expect("relation implies an entry exists");
+            if existing.borrow.get() != 0 {
+                panic!("SharedSafeAnyMap: cannot replace `{key:?}` while it is borrowed");
+            }
+        } else {
+            relations.insert(key.clone(), (requested, type_name::<T>()));
+        }
+
+        items.insert(
+            key,
+            Box::new(ErasedEntry {
+                value: UnsafeCell::new(Box::new(value)),
+                borrow: Cell::new(0),
+            }),
+        );
+
+        Ok(())
+    }
+
+    /// Borrows the value stored at `key` for shared, read-only access.
+    ///
+    /// Returns `None` if the key is absent or holds a different type.
+    /// Panics if `key` is currently held by a [`RefMut`].
+    pub fn get<T>(&self, key: &K) -> Option<Ref<'_, T>>
+    where
+        T: Any + Hash + 'static,
+    {
+        if self.relations.borrow().get(key).map(|(id, _)| *id) != Some(TypeId::of::<T>()) {
+            return None;
+        }
+
+        let items = self.items.borrow();
+        let entry_ptr: *const ErasedEntry = &**items.get(key)?;
+        drop(items);
+
+        // SAFETY: `entry_ptr` points at the heap allocation owned by the
+        // `Box<ErasedEntry>` stored in `items`. Rehashing `items` only
+        // moves that `Box` pointer around, never the allocation it points
+        // to, and `insert` refuses to replace or drop that allocation
+        // while its borrow flag is non-zero — which it's about to become,
+        // below. So this reference stays valid for as long as the `Ref`
+        // we return.
+        let entry = unsafe { &*entry_ptr };
+
+        let flag = entry.borrow.get();
+        if flag < 0 {
+            panic!("SharedSafeAnyMap: already mutably borrowed");
+        }
+        entry.borrow.set(flag + 1);
+
+        // SAFETY: the relation check above confirmed the stored value is
+        // a `T`, and `flag >= 0` means no `&mut` alias of it exists.
+        let value = unsafe { (*entry.value.get()).downcast_ref::<T>() }
+            .expect("relation was just checked to match T");
+
+        Some(Ref { value, borrow: &entry.borrow })
+    }
+
+    /// Borrows the value stored at `key` for exclusive, mutable access.
+    ///
+    /// Returns `None` if the key is absent or holds a different type.
+    /// Panics if `key` is already borrowed, shared or exclusive.
+    pub fn get_mut<T>(&self, key: &K) -> Option<RefMut<'_, T>>
+    where
+        T: Any + Hash + 'static,
+    {
+        if self.relations.borrow().get(key).map(|(id, _)| *id) != Some(TypeId::of::<T>()) {
+            return None;
+        }
+
+        let items = self.items.borrow();
+        let entry_ptr: *const ErasedEntry = &**items.get(key)?;
+        drop(items);
+
+        // SAFETY: see `get`; additionally we require the flag to be
+        // exactly `0` below before handing out an exclusive alias.
+        let entry = unsafe { &*entry_ptr };
+
+        if entry.borrow.get() != 0 {
+            panic!("SharedSafeAnyMap: already borrowed");
+        }
+        entry.borrow.set(-1);
+
+        // SAFETY: the relation check confirmed the stored value is a `T`,
+        // and the flag was `0`, so no other alias of it exists.
+        let value = unsafe { (*entry.value.get()).downcast_mut::<T>() }
+            .expect("relation was just checked to match T");
+
+        Some(RefMut { value, borrow: &entry.borrow })
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use std::cell::RefCell;
+    use std::rc::Rc;
+
+    struct DropRecorder {
+        id: &'static str,
+        log: Rc<RefCell<Vec<&'static str>>>,
+    }
+
+    impl std::hash::Hash for DropRecorder {
+        fn hash<H: std::hash::Hasher>(&self, state: &mut H) {
+            self.id.hash(state);
+        }
+    }
+
+    impl Drop for DropRecorder {
+        fn drop(&mut self) {
+            self.log.borrow_mut().push(self.id);
+        }
+    }
+
+    #[test]
+    fn insert_then_get() {
+        let mut map: SafeAnyMap<&str> = SafeAnyMap::new();
+        map.insert("a", 1u32).unwrap();
+        assert_eq!(map.get::<u32>(&"a"), Some(&1));
+    }
+
+    #[test]
+    fn overwrite_drops_previous_value_exactly_once() {
+        let log = Rc::new(RefCell::new(Vec::new()));
+        let mut map = SafeAnyMap::new_double_inserts();
+
+        map.insert("a", DropRecorder { id: "first", log: log.clone() })
+            .unwrap();
+
+        let replaced = map
+            .insert("a", DropRecorder { id: "second", log: log.clone() })
+            .unwrap();
+
+        // the old value comes back out instead of being dropped in place
+        assert!(log.borrow().is_empty());
+        drop(replaced);
+        assert_eq!(*log.borrow(), vec!["first"]);
+    }
+
+    #[test]
+    fn remove_drops_value_exactly_once() {
+        let log = Rc::new(RefCell::new(Vec::new()));
+        let mut map = SafeAnyMap::new();
+
+        map.insert("a", DropRecorder { id: "only", log: log.clone() })
+            .unwrap();
+
+        let removed = map.remove::<DropRecorder>(&"a");
+        assert!(log.borrow().is_empty());
+        drop(removed);
+        assert_eq!(*log.borrow(), vec!["only"]);
+    }
+
+    #[test]
+    fn dropping_the_map_drops_every_remaining_value_once() {
+        let log = Rc::new(RefCell::new(Vec::new()));
+        {
+            let mut map = SafeAnyMap::new();
+            map.insert("a", DropRecorder { id: "a", log: log.clone() })
+                .unwrap();
+            map.insert("b", DropRecorder { id: "b", log: log.clone() })
+                .unwrap();
+        }
+
+        let mut dropped = log.borrow().clone();
+        dropped.sort();
+        assert_eq!(dropped, vec!["a", "b"]);
+    }
+
+    #[test]
+    fn keyed_access_round_trips() {
+        let mut map: SafeAnyMap<&str> = SafeAnyMap::new();
+        let key: Key<&str, u32> = Key::new("a");
+
+        map.insert_keyed(key.clone(), 42u32).unwrap();
+        assert_eq!(map.get_keyed(&key), Some(&42));
+        assert_eq!(map.remove_keyed(&key), Some(42));
+        assert_eq!(map.get_keyed(&key), None);
+    }
+
+    #[test]
+    fn entry_or_insert_materializes_default_once() {
+        let mut map: SafeAnyMap<&str> = SafeAnyMap::new_double_inserts();
+
+        *map.entry::<u32>("a").or_insert(0).unwrap() += 1;
+        *map.entry::<u32>("a").or_insert(100).unwrap() += 1;
+
+        assert_eq!(map.get::<u32>(&"a"), Some(&2));
+    }
+
+    #[test]
+    fn entry_and_modify_skips_vacant() {
+        let mut map: SafeAnyMap<&str> = SafeAnyMap::new_double_inserts();
+
+        map.entry::<u32>("a")
+            .and_modify(|v| *v += 1)
+            .or_insert(5)
+            .unwrap();
+
+        assert_eq!(map.get::<u32>(&"a"), Some(&5));
+    }
+
+    #[test]
+    fn entry_conflicting_type_does_not_overwrite() {
+        let mut map: SafeAnyMap<&str> = SafeAnyMap::new_double_inserts();
+        map.insert("a", 1u32).unwrap();
+
+        let err = map.entry::<&str>("a").or_insert("oops").unwrap_err();
+
+        assert!(matches!(err, SafeAnyMapError::ConflictingValueType { .. }));
+        assert_eq!(map.get::<u32>(&"a"), Some(&1));
+    }
+
+    #[test]
+    fn conflicting_type_is_rejected() {
+        let mut map = SafeAnyMap::new_double_inserts();
+        map.insert("a", 1u32).unwrap();
+        let err = map.insert("a", "not a u32").unwrap_err();
+        match err {
+            SafeAnyMapError::ConflictingValueType { got, exist } => {
+                assert_eq!(got, std::any::type_name::<&str>());
+                assert_eq!(exist, std::any::type_name::<u32>());
+            }
+            other => panic!("expected ConflictingValueType, got {other:?}"),
+        }
+    }
+
+    #[test]
+    fn clone_map_snapshots_are_independent() {
+        let mut map: SafeAnyCloneMap<&str> = SafeAnyCloneMap::new();
+        map.insert("a", 1u32).unwrap();
+
+        let mut snapshot = map.clone();
+        *snapshot.get_mut::<u32>(&"a").unwrap() += 1;
+
+        assert_eq!(map.get::<u32>(&"a"), Some(&1));
+        assert_eq!(snapshot.get::<u32>(&"a"), Some(&2));
+    }
+
+    #[test]
+    fn clone_map_conflicting_type_reports_existing_type() {
+        let mut map = SafeAnyCloneMap::new_double_inserts();
+        map.insert("a", 1u32).unwrap();
+
+        let err = map.insert("a", "not a u32").unwrap_err();
+        match err {
+            SafeAnyMapError::ConflictingValueType { got, exist } => {
+                assert_eq!(got, std::any::type_name::<&str>());
+                assert_eq!(exist, std::any::type_name::<u32>());
+            }
+            other => panic!("expected ConflictingValueType, got {other:?}"),
+        }
+    }
+
+    #[test]
+    fn with_hasher_behaves_like_new() {
+        let mut map: SafeAnyMap<&str, RandomState> = SafeAnyMap::with_hasher(RandomState::new());
+        map.insert("a", 1u32).unwrap();
+        assert_eq!(map.get::<u32>(&"a"), Some(&1));
+    }
+
+    #[test]
+    fn shared_map_get_and_get_mut_round_trip() {
+        let map: SharedSafeAnyMap<&str> = SharedSafeAnyMap::new();
+        map.insert("a", 1u32).unwrap();
+
+        *map.get_mut::<u32>(&"a").unwrap() += 1;
+        assert_eq!(*map.get::<u32>(&"a").unwrap(), 2);
+    }
+
+    #[test]
+    fn shared_map_allows_concurrent_reads() {
+        let map: SharedSafeAnyMap<&str> = SharedSafeAnyMap::new();
+        map.insert("a", 1u32).unwrap();
+
+        let first = map.get::<u32>(&"a").unwrap();
+        let second = map.get::<u32>(&"a").unwrap();
+        assert_eq!((*first, *second), (1, 1));
+    }
+
+    #[test]
+    #[should_panic(expected = "already mutably borrowed")]
+    fn shared_map_read_while_mutably_borrowed_panics() {
+        let map: SharedSafeAnyMap<&str> = SharedSafeAnyMap::new();
+        map.insert("a", 1u32).unwrap();
+
+        let _write = map.get_mut::<u32>(&"a").unwrap();
+        let _read = map.get::<u32>(&"a");
+    }
+
+    #[test]
+    #[should_panic(expected = "already borrowed")]
+    fn shared_map_write_while_borrowed_panics() {
+        let map: SharedSafeAnyMap<&str> = SharedSafeAnyMap::new();
+        map.insert("a", 1u32).unwrap();
+
+        let _read = map.get::<u32>(&"a").unwrap();
+        let _write = map.get_mut::<u32>(&"a");
+    }
+
+    #[test]
+    fn shared_map_borrow_releases_on_drop() {
+        let map: SharedSafeAnyMap<&str> = SharedSafeAnyMap::new();
+        map.insert("a", 1u32).unwrap();
+
+        {
+            let _read = map.get::<u32>(&"a").unwrap();
+        }
+
+        // the read guard above is dropped, so an exclusive borrow is fine
+        *map.get_mut::<u32>(&"a").unwrap() += 1;
+        assert_eq!(*map.get::<u32>(&"a").unwrap(), 2);
+    }
+
+    #[test]
+    fn shared_map_insert_rejects_conflicting_type() {
+        let map: SharedSafeAnyMap<&str> = SharedSafeAnyMap::new_double_inserts();
+        map.insert("a", 1u32).unwrap();
+
+        let err = map.insert("a", "not a u32").unwrap_err();
+        match err {
+            SafeAnyMapError::ConflictingValueType { got, exist } => {
+                assert_eq!(got, std::any::type_name::<&str>());
+                assert_eq!(exist, std::any::type_name::<u32>());
+            }
+            other => panic!("expected ConflictingValueType, got {other:?}"),
+        }
+    }
+
+    #[test]
+    fn shared_map_double_insert_precedence_matches_safe_any_map() {
+        let map: SharedSafeAnyMap<&str> = SharedSafeAnyMap::new();
+        map.insert("a", 1u32).unwrap();
+
+        // occupied key, wrong type, double-inserts off: DoubleInsert wins,
+        // same as `SafeAnyMap::insert`.
+        let err = map.insert("a", "not a u32").unwrap_err();
+        assert!(matches!(err, SafeAnyMapError::DoubleInsert));
+    }
+}